@@ -0,0 +1,100 @@
+//! YAML-backed configuration, hot-reloadable over `SIGHUP`.
+
+use serde::Deserialize;
+use signal_hook::consts::SIGHUP;
+use signal_hook::iterator::Signals;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// The hostname of the devices we are searching for.
+    #[serde(default = "Config::default_service_name")]
+    pub service_name: String,
+
+    #[serde(default = "Config::default_timeout_secs")]
+    pub timeout_secs: u64,
+
+    #[serde(default = "Config::default_interval_secs")]
+    pub interval_secs: u64,
+
+    /// Where to write the `file_sd_config` JSON. Writes to stdout when unset.
+    #[serde(default)]
+    pub output: Option<String>,
+
+    /// Address to serve the `http_sd_config` endpoint on. Disabled when unset.
+    #[serde(default)]
+    pub listen_addr: Option<String>,
+
+    #[serde(default = "Config::default_http_path")]
+    pub http_path: String,
+}
+
+impl Config {
+    fn default_service_name() -> String {
+        "_prometheus-http._tcp.local".to_string()
+    }
+
+    fn default_timeout_secs() -> u64 {
+        60
+    }
+
+    fn default_interval_secs() -> u64 {
+        15
+    }
+
+    fn default_http_path() -> String {
+        "/".to_string()
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
+
+    /// Parses a `Config` out of the YAML file at `path`.
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("reading {}: {}", path.display(), e))?;
+        serde_yaml::from_str(&contents).map_err(|e| format!("parsing {}: {}", path.display(), e))
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            service_name: Config::default_service_name(),
+            timeout_secs: Config::default_timeout_secs(),
+            interval_secs: Config::default_interval_secs(),
+            output: None,
+            listen_addr: None,
+            http_path: Config::default_http_path(),
+        }
+    }
+}
+
+/// Spawns a thread that re-parses `path` and swaps `config`'s contents
+/// every time the process receives `SIGHUP`, waking `reloaded` so async
+/// tasks depending on the config (e.g. the discovery loop) can notice.
+pub fn watch_for_reload(config: Arc<Mutex<Config>>, path: PathBuf, reloaded: Arc<Notify>) {
+    thread::spawn(move || {
+        let mut signals = Signals::new([SIGHUP]).expect("failed to install SIGHUP handler");
+        for _ in signals.forever() {
+            match Config::load(&path) {
+                Ok(config_update) => {
+                    *config.lock().unwrap() = config_update;
+                    reloaded.notify_one();
+                    eprintln!("reloaded config from {}", path.display());
+                }
+                Err(e) => eprintln!("failed to reload config: {}", e),
+            }
+        }
+    });
+}
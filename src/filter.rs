@@ -0,0 +1,114 @@
+//! Allowlist/denylist filtering of discovered services by name and subnet.
+
+use ipnet::IpNet;
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Read};
+use std::net::IpAddr;
+use std::path::Path;
+
+/// A set of name patterns and subnets resolved from a `--include`/`--exclude`
+/// value.
+#[derive(Debug, Default, Clone)]
+pub struct MatchSet {
+    names: HashSet<String>,
+    nets: Vec<IpNet>,
+}
+
+impl MatchSet {
+    /// Resolves a `--include`/`--exclude` value into a `MatchSet`. `value`
+    /// is either a literal pattern, a path to a file of newline-separated
+    /// patterns, or `-` to read patterns from stdin. Each pattern is parsed
+    /// as a CIDR subnet if possible, otherwise treated as a literal name.
+    pub fn parse(value: &str) -> Result<MatchSet, String> {
+        let patterns = if value == "-" {
+            let mut buf = String::new();
+            io::stdin()
+                .read_to_string(&mut buf)
+                .map_err(|e| e.to_string())?;
+            buf
+        } else if Path::new(value).is_file() {
+            fs::read_to_string(value).map_err(|e| e.to_string())?
+        } else {
+            value.to_string()
+        };
+
+        let mut set = MatchSet::default();
+        for pattern in patterns.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            match pattern.parse::<IpNet>() {
+                Ok(net) => set.nets.push(net),
+                Err(_) => {
+                    set.names.insert(pattern.to_string());
+                }
+            }
+        }
+        Ok(set)
+    }
+
+    pub fn extend(&mut self, other: MatchSet) {
+        self.names.extend(other.names);
+        self.nets.extend(other.nets);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.nets.is_empty()
+    }
+
+    fn matches(&self, name: Option<&str>, addr: IpAddr) -> bool {
+        if let Some(name) = name {
+            if self.names.contains(name) {
+                return true;
+            }
+        }
+        self.nets.iter().any(|net| net.contains(&addr))
+    }
+}
+
+/// A service is emitted only if it passes `include` (or `include` is empty)
+/// and does not match `exclude`.
+pub struct Filter {
+    include: MatchSet,
+    exclude: MatchSet,
+}
+
+impl Filter {
+    pub fn new(include: MatchSet, exclude: MatchSet) -> Filter {
+        Filter { include, exclude }
+    }
+
+    pub fn allows(&self, name: Option<&str>, addr: IpAddr) -> bool {
+        (self.include.is_empty() || self.include.matches(name, addr))
+            && !self.exclude.matches(name, addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_set_parse_splits_names_from_subnets() {
+        let set = MatchSet::parse("foo\n10.0.0.0/8\nbar").unwrap();
+        assert!(set.matches(Some("foo"), "1.2.3.4".parse().unwrap()));
+        assert!(set.matches(Some("bar"), "1.2.3.4".parse().unwrap()));
+        assert!(set.matches(None, "10.1.2.3".parse().unwrap()));
+        assert!(!set.matches(Some("baz"), "1.2.3.4".parse().unwrap()));
+    }
+
+    #[test]
+    fn filter_allows_requires_include_and_rejects_exclude() {
+        let include = MatchSet::parse("10.0.0.0/8").unwrap();
+        let exclude = MatchSet::parse("10.0.0.1").unwrap();
+        let filter = Filter::new(include, exclude);
+
+        assert!(filter.allows(None, "10.0.0.2".parse().unwrap()));
+        assert!(!filter.allows(Some("10.0.0.1"), "10.0.0.1".parse().unwrap()));
+        assert!(!filter.allows(None, "192.168.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn filter_allows_everything_when_include_is_empty() {
+        let filter = Filter::new(MatchSet::default(), MatchSet::default());
+        assert!(filter.allows(Some("anything"), "127.0.0.1".parse().unwrap()));
+    }
+}
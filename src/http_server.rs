@@ -0,0 +1,66 @@
+//! Embedded `http_sd_config` endpoint.
+
+use crate::filter::Filter;
+use crate::{PrometheusService, Service};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, RwLock};
+
+/// Serves the current contents of `services` as a JSON `http_sd_config`
+/// target list at `path`, listening on `addr`. Runs until the process exits.
+pub async fn serve(
+    services: Arc<RwLock<HashMap<(IpAddr, u16), Service>>>,
+    addr: SocketAddr,
+    path: String,
+    filter: Arc<Filter>,
+) {
+    let make_service = make_service_fn(move |_conn| {
+        let services = services.clone();
+        let path = path.clone();
+        let filter = filter.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let services = services.clone();
+                let path = path.clone();
+                let filter = filter.clone();
+                async move {
+                    if req.uri().path() != path {
+                        return Ok::<_, Infallible>(
+                            Response::builder().status(404).body(Body::empty()).unwrap(),
+                        );
+                    }
+
+                    let output: Vec<PrometheusService> = services
+                        .read()
+                        .unwrap()
+                        .values()
+                        .filter(|service| {
+                            filter.allows(service.labels.get("name").map(String::as_str), service.addr)
+                        })
+                        .map(PrometheusService::from)
+                        .collect();
+
+                    let response = match serde_json::to_string(&output) {
+                        Ok(body) => Response::builder()
+                            .header("Content-Type", "application/json")
+                            .body(Body::from(body))
+                            .unwrap(),
+                        Err(e) => Response::builder()
+                            .status(500)
+                            .body(Body::from(e.to_string()))
+                            .unwrap(),
+                    };
+
+                    Ok(response)
+                }
+            }))
+        }
+    });
+
+    if let Err(e) = Server::bind(&addr).serve(make_service).await {
+        eprintln!("http_sd_config server error: {}", e);
+    }
+}
@@ -1,25 +1,28 @@
 use atomicwrites::{AllowOverwrite, AtomicFile};
-use futures::{Future, Stream};
-use maplit::hashmap;
+use futures_util::StreamExt;
 use mdns::{Record, RecordKind};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::env;
 use std::io::Write;
-use std::sync::mpsc;
-use std::sync::mpsc::Sender;
-use std::thread;
-use std::thread::sleep;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::Instant;
 use std::{net::IpAddr, time::Duration};
+use tokio::sync::{mpsc, Notify};
 
-/// The hostname of the devices we are searching for.
-const SERVICE_NAME: &'static str = "_prometheus-http._tcp.local";
+use config::Config;
+use filter::{Filter, MatchSet};
+
+mod config;
+mod filter;
+mod http_server;
+mod netwatch;
 
 struct Service {
-    name: String,
     addr: IpAddr,
     port: u16,
+    labels: HashMap<String, String>,
     last_seen: Instant,
 }
 
@@ -33,87 +36,198 @@ impl From<&Service> for PrometheusService {
     fn from(service: &Service) -> Self {
         PrometheusService {
             targets: vec![format!("{}:{}", service.addr, service.port)],
-            labels: hashmap! {
-                "name".to_string() => service.name.clone()
-            },
+            labels: service.labels.clone(),
         }
     }
 }
 
-const TIMEOUT: Duration = Duration::from_secs(60);
-const INTERVAL: Duration = Duration::from_secs(15);
+#[tokio::main]
+async fn main() {
+    let mut config_path: Option<PathBuf> = None;
+    let mut include = MatchSet::default();
+    let mut exclude = MatchSet::default();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--include" => {
+                let value = args.next().expect("--include requires a value");
+                include.extend(MatchSet::parse(&value).unwrap_or_else(|e| panic!("{}", e)));
+            }
+            "--exclude" => {
+                let value = args.next().expect("--exclude requires a value");
+                exclude.extend(MatchSet::parse(&value).unwrap_or_else(|e| panic!("{}", e)));
+            }
+            path => config_path = Some(PathBuf::from(path)),
+        }
+    }
 
-fn main() {
-    let out = env::args()
-        .skip(1)
-        .next()
-        .map(|path| AtomicFile::new(path, AllowOverwrite));
+    let filter = Arc::new(Filter::new(include, exclude));
 
-    let (tx, rx) = mpsc::channel();
+    let config = Arc::new(Mutex::new(match &config_path {
+        Some(path) => Config::load(path).unwrap_or_else(|e| panic!("{}", e)),
+        None => Config::default(),
+    }));
 
-    thread::spawn(move || {
-        discover(tx);
-    });
+    let reloaded = Arc::new(Notify::new());
+    if let Some(path) = &config_path {
+        config::watch_for_reload(config.clone(), path.clone(), reloaded.clone());
+    }
 
-    let mut services: HashMap<IpAddr, Service> = HashMap::new();
+    let (listen_addr, http_path) = {
+        let config = config.lock().unwrap();
+        (config.listen_addr.clone(), config.http_path.clone())
+    };
+
+    let services: Arc<RwLock<HashMap<(IpAddr, u16), Service>>> = Arc::new(RwLock::new(HashMap::new()));
+    let iface_changes = netwatch::watcher().watch();
+
+    tokio::spawn(supervise_discover(config.clone(), services.clone(), reloaded, iface_changes));
+
+    if let Some(addr) = listen_addr {
+        let addr = addr.parse().expect("invalid listen_addr");
+        let services = services.clone();
+        tokio::spawn(http_server::serve(services, addr, http_path, filter.clone()));
+    }
 
     loop {
-        let start_count = services.len();
+        let tick = config.lock().unwrap().interval();
+        tokio::time::sleep(tick).await;
 
-        while let Ok(service) = rx.try_recv() {
-            services.insert(service.addr, service);
-        }
+        let config = config.lock().unwrap().clone();
+        let mut services = services.write().unwrap();
 
-        let added_count = services.len();
+        services.retain(|_, service| {
+            Instant::now().duration_since(service.last_seen) < config.timeout()
+        });
 
-        services.retain(|_, service| Instant::now().duration_since(service.last_seen) < TIMEOUT);
+        let output_services: Vec<PrometheusService> = services
+            .iter()
+            .filter(|(_, service)| {
+                filter.allows(service.labels.get("name").map(String::as_str), service.addr)
+            })
+            .map(|(_, service)| service.into())
+            .collect();
+        let output = serde_json::to_string(&output_services).unwrap();
 
-        let removed_count = services.len();
+        match &config.output {
+            Some(path) => {
+                let file = AtomicFile::new(path, AllowOverwrite);
+                let _ = file.write(|f| f.write_all(output.as_bytes()));
+            }
+            None => println!("{}", output),
+        }
+    }
+}
 
-        if start_count != added_count || added_count != removed_count {
-            let output_services: Vec<PrometheusService> =
-                services.iter().map(|(_, service)| service.into()).collect();
-            let output = serde_json::to_string(&output_services).unwrap();
+/// Runs `discover` for the config's current `service_name`, restarting it
+/// whenever a `SIGHUP` reload changes that name or a network interface
+/// changes (forcing a fresh mDNS query instead of waiting for the next
+/// scheduled one).
+async fn supervise_discover(
+    config: Arc<Mutex<Config>>,
+    services: Arc<RwLock<HashMap<(IpAddr, u16), Service>>>,
+    reloaded: Arc<Notify>,
+    mut iface_changes: mpsc::Receiver<()>,
+) {
+    let mut service_name = config.lock().unwrap().service_name.clone();
+    loop {
+        let interval = config.lock().unwrap().interval();
+        let task = tokio::spawn(discover(services.clone(), service_name.clone(), interval));
 
-            match &out {
-                Some(path) => {
-                    let _ = path.write(|f| f.write_all(output.as_bytes()));
+        loop {
+            tokio::select! {
+                _ = reloaded.notified() => {
+                    let current = config.lock().unwrap().service_name.clone();
+                    if current != service_name {
+                        service_name = current;
+                        task.abort();
+                        break;
+                    }
+                }
+                Some(()) = iface_changes.recv() => {
+                    services.write().unwrap().clear();
+                    task.abort();
+                    break;
                 }
-                None => println!("{}", output),
             }
         }
+    }
+}
 
-        sleep(INTERVAL);
+async fn discover(
+    services: Arc<RwLock<HashMap<(IpAddr, u16), Service>>>,
+    service_name: String,
+    interval: Duration,
+) {
+    let responses = mdns::discover::all(service_name.clone(), interval)
+        .unwrap()
+        .listen();
+    futures_util::pin_mut!(responses);
+
+    while let Some(response) = responses.next().await {
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => {
+                eprintln!("{:?}", e);
+                continue;
+            }
+        };
+
+        let records: Vec<&Record> = response.records().collect();
+
+        for (addr, port, labels) in self::to_instances(&records, &service_name) {
+            services.write().unwrap().insert(
+                (addr, port),
+                Service {
+                    addr,
+                    port,
+                    labels,
+                    last_seen: Instant::now(),
+                },
+            );
+        }
     }
 }
 
-fn discover(tx: Sender<Service>) {
-    tokio::run(
-        mdns::discover::all(SERVICE_NAME, INTERVAL)
-            .unwrap()
-            .for_each(move |response| {
-                if response
-                    .records()
-                    .any(|record| record.name.as_str() == SERVICE_NAME)
-                {
-                    let addr = response.records().filter_map(self::to_ip_addr).next();
-                    let port = response.records().filter_map(self::to_port).next();
-                    let name = response.records().filter_map(self::to_name).next();
-
-                    if let (Some(addr), Some(name), Some(port)) = (addr, name, port) {
-                        let _ = tx.send(Service {
-                            name,
-                            addr,
-                            port,
-                            last_seen: Instant::now(),
-                        });
-                    }
-                }
+/// Groups a response's records by SRV instance name, pairing each instance's
+/// port and target host with its resolved address and TXT labels. A single
+/// response can advertise more than one instance of `service_name`.
+fn to_instances(
+    records: &[&Record],
+    service_name: &str,
+) -> Vec<(IpAddr, u16, HashMap<String, String>)> {
+    records
+        .iter()
+        .copied()
+        .filter_map(|record| match &record.kind {
+            RecordKind::SRV { port, target, .. } if record.name.contains(service_name) => {
+                Some((record.name.as_str(), target.as_str(), *port))
+            }
+            _ => None,
+        })
+        .filter_map(|(instance_name, target, port)| {
+            let addr = records
+                .iter()
+                .copied()
+                .find(|record| {
+                    record.name == target
+                        && matches!(record.kind, RecordKind::A(_) | RecordKind::AAAA(_))
+                })
+                .and_then(self::to_ip_addr)?;
 
-                Ok(())
-            })
-            .map_err(|e| eprintln!("{:?}", e)),
-    );
+            let labels = records
+                .iter()
+                .copied()
+                .find(|record| {
+                    record.name == instance_name && matches!(record.kind, RecordKind::TXT(_))
+                })
+                .and_then(self::to_labels)
+                .unwrap_or_default();
+
+            Some((addr, port, labels))
+        })
+        .collect()
 }
 
 fn to_ip_addr(record: &Record) -> Option<IpAddr> {
@@ -124,23 +238,131 @@ fn to_ip_addr(record: &Record) -> Option<IpAddr> {
     }
 }
 
-fn to_port(record: &Record) -> Option<u16> {
-    match record.kind {
-        RecordKind::SRV { port, .. } if record.name.contains(SERVICE_NAME) => Some(port),
-        _ => None,
-    }
-}
-
-fn to_name(record: &Record) -> Option<String> {
+/// Collects every `key=value` pair in a TXT record into a label map, with
+/// keys sanitized into valid Prometheus label names: non-`[a-zA-Z0-9_]`
+/// characters become `_`, a leading digit is prefixed with `_`, and empty
+/// keys are dropped.
+fn to_labels(record: &Record) -> Option<HashMap<String, String>> {
     if let RecordKind::TXT(txt) = &record.kind {
+        let mut labels = HashMap::new();
         for pair in txt {
-            let mut parts = pair.split('=');
-            if let (Some(key), Some(value)) = (parts.next(), parts.next()) {
-                if key == "name" {
-                    return Some(value.to_string());
-                }
+            let mut parts = pair.splitn(2, '=');
+            let key = parts.next().unwrap_or("");
+            let value = parts.next().unwrap_or("");
+            if let Some(key) = self::sanitize_label_key(key) {
+                labels.insert(key, value.to_string());
             }
         }
+        Some(labels)
+    } else {
+        None
+    }
+}
+
+/// Turns a TXT key into a valid Prometheus label name, or `None` if the key
+/// is empty.
+fn sanitize_label_key(key: &str) -> Option<String> {
+    if key.is_empty() {
+        return None;
+    }
+
+    let mut sanitized: String = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .collect();
+
+    if sanitized.chars().next().unwrap().is_ascii_digit() {
+        sanitized.insert(0, '_');
+    }
+
+    Some(sanitized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dns_parser::Class;
+
+    fn record(name: &str, kind: RecordKind) -> Record {
+        Record { name: name.to_string(), class: Class::IN, ttl: 120, kind }
+    }
+
+    #[test]
+    fn to_instances_resolves_addr_and_labels_even_when_srv_precedes_txt() {
+        let records = [
+            record(
+                "foo._prometheus-http._tcp.local",
+                RecordKind::SRV {
+                    priority: 0,
+                    weight: 0,
+                    port: 9100,
+                    target: "foo.local".to_string(),
+                },
+            ),
+            record(
+                "foo._prometheus-http._tcp.local",
+                RecordKind::TXT(vec!["name=foo".to_string()]),
+            ),
+            record("foo.local", RecordKind::A([10, 0, 0, 1].into())),
+        ];
+        let records: Vec<&Record> = records.iter().collect();
+
+        let instances = to_instances(&records, "_prometheus-http._tcp.local");
+
+        assert_eq!(instances.len(), 1);
+        let (addr, port, labels) = &instances[0];
+        assert_eq!(*addr, IpAddr::from([10, 0, 0, 1]));
+        assert_eq!(*port, 9100);
+        assert_eq!(labels.get("name"), Some(&"foo".to_string()));
+    }
+
+    #[test]
+    fn to_instances_keeps_multiple_instances_on_the_same_host() {
+        let records = [
+            record(
+                "node._prometheus-http._tcp.local",
+                RecordKind::SRV {
+                    priority: 0,
+                    weight: 0,
+                    port: 9100,
+                    target: "foo.local".to_string(),
+                },
+            ),
+            record(
+                "node._prometheus-http._tcp.local",
+                RecordKind::TXT(vec!["name=node".to_string()]),
+            ),
+            record(
+                "other._prometheus-http._tcp.local",
+                RecordKind::SRV {
+                    priority: 0,
+                    weight: 0,
+                    port: 9200,
+                    target: "foo.local".to_string(),
+                },
+            ),
+            record(
+                "other._prometheus-http._tcp.local",
+                RecordKind::TXT(vec!["name=other".to_string()]),
+            ),
+            record("foo.local", RecordKind::A([10, 0, 0, 1].into())),
+        ];
+        let records: Vec<&Record> = records.iter().collect();
+
+        let mut instances = to_instances(&records, "_prometheus-http._tcp.local");
+        instances.sort_by_key(|(_, port, _)| *port);
+
+        assert_eq!(instances.len(), 2);
+        assert_eq!(instances[0].1, 9100);
+        assert_eq!(instances[0].2.get("name"), Some(&"node".to_string()));
+        assert_eq!(instances[1].1, 9200);
+        assert_eq!(instances[1].2.get("name"), Some(&"other".to_string()));
+    }
+
+    #[test]
+    fn sanitize_label_key_replaces_invalid_characters_and_leading_digits() {
+        assert_eq!(sanitize_label_key("some-key"), Some("some_key".to_string()));
+        assert_eq!(sanitize_label_key("1key"), Some("_1key".to_string()));
+        assert_eq!(sanitize_label_key(""), None);
     }
-    None
 }
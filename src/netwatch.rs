@@ -0,0 +1,79 @@
+//! Network interface change notifications.
+
+use tokio::sync::mpsc;
+
+/// Notifies the caller whenever a network interface's link or address
+/// state changes.
+pub trait InterfaceWatcher: Send {
+    fn watch(self: Box<Self>) -> mpsc::Receiver<()>;
+}
+
+/// Returns the `InterfaceWatcher` for the current platform.
+pub fn watcher() -> Box<dyn InterfaceWatcher> {
+    #[cfg(target_os = "linux")]
+    {
+        Box::new(linux::NetlinkWatcher)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        Box::new(NullWatcher)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+struct NullWatcher;
+
+#[cfg(not(target_os = "linux"))]
+impl InterfaceWatcher for NullWatcher {
+    fn watch(self: Box<Self>) -> mpsc::Receiver<()> {
+        let (tx, rx) = mpsc::channel(1);
+        tokio::spawn(async move {
+            let _tx = tx;
+            std::future::pending::<()>().await;
+        });
+        rx
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::InterfaceWatcher;
+    use futures_util::StreamExt;
+    use rtnetlink::new_multicast_connection;
+    use rtnetlink::MulticastGroup;
+    use tokio::sync::mpsc;
+
+    pub struct NetlinkWatcher;
+
+    impl InterfaceWatcher for NetlinkWatcher {
+        fn watch(self: Box<Self>) -> mpsc::Receiver<()> {
+            let (tx, rx) = mpsc::channel(16);
+
+            tokio::spawn(async move {
+                let groups = [
+                    MulticastGroup::Link,
+                    MulticastGroup::Ipv4Ifaddr,
+                    MulticastGroup::Ipv6Ifaddr,
+                ];
+
+                let (connection, _handle, mut messages) = match new_multicast_connection(&groups) {
+                    Ok(parts) => parts,
+                    Err(e) => {
+                        eprintln!("failed to open netlink socket: {}", e);
+                        return;
+                    }
+                };
+                tokio::spawn(connection);
+
+                while messages.next().await.is_some() {
+                    if tx.send(()).await.is_err() {
+                        break;
+                    }
+                }
+            });
+
+            rx
+        }
+    }
+}